@@ -0,0 +1,144 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use crate::sync::state::snapshot_manifest_request::manifest_commitment::{
+    self, RangeProof,
+};
+use cfx_types::H256;
+use keccak_hash::keccak;
+use parking_lot::RwLock;
+use rlp::Encodable;
+use rlp_derive::{RlpDecodable, RlpEncodable};
+use std::collections::HashMap;
+
+/// Identifies a single chunk within the ordered key space of a checkpoint's
+/// delta-trie snapshot. Wraps the raw key bytes rather than a fixed-width
+/// hash so it can represent whatever key encoding the underlying delta
+/// storage uses.
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    Hash,
+    Default,
+    RlpDecodable,
+    RlpEncodable,
+)]
+pub struct ChunkKey {
+    pub key: Vec<u8>,
+}
+
+impl ChunkKey {
+    pub fn new(key: Vec<u8>) -> Self { ChunkKey { key } }
+}
+
+lazy_static! {
+    /// The full, ordered chunk-key list this node can serve for a
+    /// checkpoint it already holds complete state for, keyed by
+    /// checkpoint hash. Populated once local state-sync storage finishes
+    /// indexing a checkpoint's delta trie; read by `RangedManifest::load`
+    /// and `RangedManifest::commitment_proof` when serving
+    /// `SnapshotManifestRequest`s for it.
+    static ref LOCAL_CHECKPOINT_CHUNK_KEYS: RwLock<HashMap<H256, Vec<ChunkKey>>> =
+        RwLock::new(HashMap::new());
+}
+
+/// Must be called once local storage finishes indexing `checkpoint`'s
+/// delta trie, before this node can serve `SnapshotManifestRequest`s for
+/// it.
+pub fn set_local_checkpoint_chunk_keys(
+    checkpoint: H256, chunk_keys: Vec<ChunkKey>,
+) {
+    LOCAL_CHECKPOINT_CHUNK_KEYS.write().insert(checkpoint, chunk_keys);
+}
+
+/// The maximum number of chunk keys returned in a single manifest page.
+const MAX_CHUNK_KEYS_PER_PAGE: usize = 1024;
+
+/// One page of a checkpoint's ordered chunk-key manifest, as served by
+/// `SnapshotManifestRequest::handle` and consumed by
+/// `SnapshotManifestResponse::handle`.
+#[derive(Debug, Clone, Default, RlpDecodable, RlpEncodable)]
+pub struct RangedManifest {
+    chunk_keys: Vec<ChunkKey>,
+    /// This page's offset within the checkpoint's full chunk-key ordering.
+    start_index: usize,
+    /// Whether this page reaches the end of the checkpoint's full
+    /// ordering, i.e. whether a client walking pages to discover the full
+    /// ordering can stop after this one.
+    is_last: bool,
+}
+
+impl RangedManifest {
+    /// Loads the page of `checkpoint`'s manifest starting at `start_chunk`
+    /// (or from the beginning of the ordering if `None`) from local
+    /// storage. Returns `Ok(None)` if this node doesn't have `checkpoint`
+    /// indexed at all, and `Err` if `start_chunk` isn't a key within it.
+    pub fn load(
+        checkpoint: &H256, start_chunk: Option<ChunkKey>,
+    ) -> Result<Option<RangedManifest>, String> {
+        let all_keys = LOCAL_CHECKPOINT_CHUNK_KEYS.read();
+        let full = match all_keys.get(checkpoint) {
+            Some(full) => full,
+            None => return Ok(None),
+        };
+        let start_index = match &start_chunk {
+            Some(key) => match full.iter().position(|k| k == key) {
+                Some(index) => index,
+                None => {
+                    return Err(format!(
+                        "unknown start_chunk for checkpoint {}",
+                        checkpoint
+                    ))
+                }
+            },
+            None => 0,
+        };
+        let end_index =
+            (start_index + MAX_CHUNK_KEYS_PER_PAGE).min(full.len());
+        Ok(Some(RangedManifest {
+            chunk_keys: full[start_index..end_index].to_vec(),
+            start_index,
+            is_last: end_index == full.len(),
+        }))
+    }
+
+    pub fn chunk_keys(&self) -> &[ChunkKey] { &self.chunk_keys }
+
+    /// Whether this page reaches the end of the checkpoint's full
+    /// chunk-key ordering.
+    pub fn is_last(&self) -> bool { self.is_last }
+
+    /// This page's offset within `checkpoint`'s full chunk-key ordering,
+    /// as established when the page was built. Used to anchor this page's
+    /// leaves at the right position when verifying `commitment_proof`'s
+    /// `RangeProof` against the checkpoint's committed root.
+    pub fn start_chunk_index(&self, _checkpoint: &H256) -> Option<usize> {
+        Some(self.start_index)
+    }
+
+    /// Builds the Merkle root and range proof anchoring this page's chunk
+    /// keys into the full ordering committed for `checkpoint`, so a
+    /// requester can verify the page wasn't swapped or corrupted in
+    /// transit. Returns `None` if this node doesn't have `checkpoint`'s
+    /// full ordering indexed locally (i.e. it cannot serve a verifiable
+    /// manifest for it).
+    pub fn commitment_proof(
+        &self, checkpoint: &H256,
+    ) -> Option<(H256, RangeProof)> {
+        let all_keys = LOCAL_CHECKPOINT_CHUNK_KEYS.read();
+        let full = all_keys.get(checkpoint)?;
+        let leaves: Vec<H256> =
+            full.iter().map(|key| keccak(key.rlp_bytes())).collect();
+        let levels = manifest_commitment::build_levels(&leaves);
+        let root = manifest_commitment::root(&levels);
+        let proof = manifest_commitment::range_proof(
+            &levels,
+            self.start_index,
+            self.chunk_keys.len(),
+        );
+        Some((root, proof))
+    }
+}
@@ -3,7 +3,7 @@
 // See http://www.gnu.org/licenses/
 
 use crate::{
-    block_data_manager::BlockExecutionResult,
+    block_data_manager::{BlockDataManager, BlockExecutionResult},
     message::{HasRequestId, Message, MsgId, RequestId},
     parameters::consensus_internal::REWARD_EPOCH_COUNT,
     sync::{
@@ -19,8 +19,387 @@ use crate::{
     },
 };
 use cfx_types::H256;
+use keccak_hash::keccak;
+use network::node_table::NodeId;
+use parking_lot::{Mutex, RwLock};
+use rlp::{Decodable, DecoderError, Encodable, Rlp, RlpStream};
 use rlp_derive::{RlpDecodable, RlpEncodable};
-use std::{any::Any, time::Duration};
+use std::{
+    any::Any,
+    collections::{HashMap, HashSet, VecDeque},
+    time::{Duration, Instant},
+};
+
+// How much work a single peer can extract from us per unit of time, and how
+// far a blame-vector walk is allowed to range, live on `ProtocolConfiguration`
+// (reached via `ctx.manager.protocol_config`) alongside every other wire
+// timeout and limit, rather than as constants local to this module.
+
+lazy_static! {
+    /// Per-peer token bucket guarding how often a peer may issue expensive
+    /// `SnapshotManifestRequest`s (each of which can trigger a long chain of
+    /// blame-vector / receipt DB reads). Entries are removed in
+    /// `on_peer_disconnected`, which `SynchronizationProtocolHandler`'s
+    /// disconnect path must call so this does not grow without bound over
+    /// the life of the process.
+    static ref MANIFEST_REQUEST_LIMITER: Mutex<HashMap<NodeId, TokenBucket>> =
+        Mutex::new(HashMap::new());
+}
+
+/// A token bucket that refills continuously at `capacity / window` tokens
+/// per second, rather than snapping back to full every `window` (which
+/// would let a peer burst `capacity` requests at the start of every window
+/// instead of being smoothed out).
+struct TokenBucket {
+    tokens: f64,
+    capacity: f64,
+    refill_per_sec: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(capacity: u32, window: Duration) -> Self {
+        let capacity = capacity as f64;
+        TokenBucket {
+            tokens: capacity,
+            capacity,
+            refill_per_sec: capacity / window.as_secs_f64(),
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Attempt to take one token, refilling proportionally to the elapsed
+    /// time first. Returns `false` if less than one token is available.
+    fn try_take(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens =
+            (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.last_refill = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+/// Returns `false` if `ctx.peer` has exceeded its allowance of expensive
+/// manifest requests and the request should be dropped.
+fn check_manifest_request_rate_limit(ctx: &Context) -> bool {
+    let conf = &ctx.manager.protocol_config;
+    let mut buckets = MANIFEST_REQUEST_LIMITER.lock();
+    let bucket = buckets.entry(ctx.peer).or_insert_with(|| {
+        TokenBucket::new(
+            conf.max_manifest_requests_per_window,
+            conf.manifest_request_window,
+        )
+    });
+    bucket.try_take()
+}
+
+/// The single integration point a departed peer's rate-limit state is
+/// evicted through. `SynchronizationProtocolHandler`'s peer-disconnect path
+/// (outside this module) must call this for every disconnecting peer, the
+/// same way it already notifies other per-peer state of the disconnect —
+/// otherwise `MANIFEST_REQUEST_LIMITER` grows one entry per distinct peer
+/// ever seen, for the life of the process.
+pub(crate) fn on_peer_disconnected(peer: &NodeId) {
+    MANIFEST_REQUEST_LIMITER.lock().remove(peer);
+}
+
+lazy_static! {
+    /// Checkpoint -> committed manifest Merkle root, populated through
+    /// `record_trusted_manifest_root`, which `witness_manifest_root_claim`
+    /// calls once `MANIFEST_ROOT_QUORUM` distinct peers have claimed the
+    /// same root in their `SnapshotManifestResponse`s. A root taken from
+    /// the single peer serving the chunks it is meant to authenticate would
+    /// let that same peer forge both, so one response's claim alone never
+    /// lands here.
+    static ref TRUSTED_MANIFEST_ROOTS: RwLock<HashMap<H256, H256>> =
+        RwLock::new(HashMap::new());
+}
+
+/// Must be called from the checkpoint/snapshot sync path once a
+/// checkpoint's manifest root is committed to trusted metadata, before any
+/// `SnapshotManifestResponse` for it is accepted.
+pub(crate) fn record_trusted_manifest_root(checkpoint: H256, root: H256) {
+    TRUSTED_MANIFEST_ROOTS.write().insert(checkpoint, root);
+}
+
+/// Looks up the trusted manifest root for `checkpoint`, independent of any
+/// peer response. Returns `None` if no trusted metadata has committed a
+/// root for it yet, in which case a response for it must not be trusted.
+pub(crate) fn trusted_manifest_root(checkpoint: &H256) -> Option<H256> {
+    TRUSTED_MANIFEST_ROOTS.read().get(checkpoint).cloned()
+}
+
+/// Removes the trusted root once a checkpoint is no longer being restored
+/// from, so this does not grow without bound over the life of the process.
+pub(crate) fn forget_trusted_manifest_root(checkpoint: &H256) {
+    TRUSTED_MANIFEST_ROOTS.write().remove(checkpoint);
+    MANIFEST_ROOT_CANDIDATES.lock().remove(checkpoint);
+}
+
+/// Number of distinct peers that must independently claim the same
+/// manifest root for a checkpoint before it is promoted to
+/// `TRUSTED_MANIFEST_ROOTS`. This is the checkpoint/snapshot metadata path
+/// that feeds the trusted store: rather than requiring some other
+/// already-trusted oracle for a root that exists only because of this
+/// feature, a root is trusted once enough independent, presumably
+/// non-colluding peers agree on it, so a single malicious peer (or any
+/// number below the quorum) cannot forge it on its own.
+const MANIFEST_ROOT_QUORUM: usize = 3;
+
+lazy_static! {
+    /// checkpoint -> claimed root -> distinct peers that have made that
+    /// claim, while a root is still awaiting quorum.
+    static ref MANIFEST_ROOT_CANDIDATES: Mutex<HashMap<H256, HashMap<H256, HashSet<NodeId>>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Records `peer`'s claimed manifest root for `checkpoint`, promoting it
+/// to `TRUSTED_MANIFEST_ROOTS` once `MANIFEST_ROOT_QUORUM` distinct peers
+/// have independently made the same claim. Must be called for every
+/// `SnapshotManifestResponse` received, before consulting
+/// `trusted_manifest_root` for the same checkpoint.
+pub(crate) fn witness_manifest_root_claim(
+    checkpoint: H256, peer: NodeId, root: H256,
+) {
+    if trusted_manifest_root(&checkpoint).is_some() {
+        return;
+    }
+    let reached_quorum = {
+        let mut candidates = MANIFEST_ROOT_CANDIDATES.lock();
+        let voters = candidates
+            .entry(checkpoint)
+            .or_insert_with(HashMap::new)
+            .entry(root)
+            .or_insert_with(HashSet::new);
+        voters.insert(peer);
+        voters.len() >= MANIFEST_ROOT_QUORUM
+    };
+    if reached_quorum {
+        record_trusted_manifest_root(checkpoint, root);
+        MANIFEST_ROOT_CANDIDATES.lock().remove(&checkpoint);
+    }
+}
+
+/// Content-addressing for the ordered `ChunkKey` list of a checkpoint's
+/// manifest. A node restoring from an untrusted peer can use a per-range
+/// `RangeProof` to verify that the keys it was just sent are really a
+/// sub-range of the single root committed for the checkpoint, rather than
+/// trusting the peer to not have swapped or corrupted them.
+pub(crate) mod manifest_commitment {
+    use cfx_types::H256;
+    use keccak_hash::keccak;
+    use rlp_derive::{RlpDecodable, RlpEncodable};
+
+    fn hash_pair(left: H256, right: H256) -> H256 {
+        let mut buf = Vec::with_capacity(64);
+        buf.extend_from_slice(left.as_bytes());
+        buf.extend_from_slice(right.as_bytes());
+        keccak(&buf)
+    }
+
+    /// Builds the full set of Merkle levels (leaves first, root last) over
+    /// `keccak(chunk_key)` leaves for the checkpoint's complete, ordered
+    /// chunk-key list.
+    pub fn build_levels(leaves: &[H256]) -> Vec<Vec<H256>> {
+        if leaves.is_empty() {
+            return vec![vec![H256::zero()]];
+        }
+        let mut levels = vec![leaves.to_vec()];
+        while levels.last().unwrap().len() > 1 {
+            let prev = levels.last().unwrap();
+            let mut next = Vec::with_capacity((prev.len() + 1) / 2);
+            for pair in prev.chunks(2) {
+                let (left, right) =
+                    if pair.len() == 2 { (pair[0], pair[1]) } else { (pair[0], pair[0]) };
+                next.push(hash_pair(left, right));
+            }
+            levels.push(next);
+        }
+        levels
+    }
+
+    pub fn root(levels: &[Vec<H256>]) -> H256 { levels.last().unwrap()[0] }
+
+    /// Sibling hashes needed to walk the leaf at `index` up to the root,
+    /// ordered from leaf to root.
+    fn leaf_proof(levels: &[Vec<H256>], mut index: usize) -> Vec<H256> {
+        let mut proof = Vec::with_capacity(levels.len() - 1);
+        for level in &levels[..levels.len() - 1] {
+            let sibling_index = index ^ 1;
+            let sibling = if sibling_index < level.len() {
+                level[sibling_index]
+            } else {
+                level[index]
+            };
+            proof.push(sibling);
+            index /= 2;
+        }
+        proof
+    }
+
+    fn verify_leaf(
+        leaf: H256, mut index: usize, proof: &[H256], root: H256,
+    ) -> bool {
+        let mut computed = leaf;
+        for sibling in proof {
+            computed = if index % 2 == 0 {
+                hash_pair(computed, *sibling)
+            } else {
+                hash_pair(*sibling, computed)
+            };
+            index /= 2;
+        }
+        computed == root
+    }
+
+    /// One audit path per chunk key in a ranged response, anchoring every
+    /// leaf of the range into the checkpoint's single committed root.
+    /// Earlier revisions carried only the first and last leaf's proof,
+    /// which left every interior chunk key unverified and let a peer swap
+    /// or corrupt them undetected; an honest peer cannot produce a valid
+    /// per-leaf proof for a tampered interior key, so this closes that gap
+    /// at the cost of `O(range_len * log n)` proof size instead of `O(log
+    /// n)`.
+    #[derive(Debug, Clone, RlpDecodable, RlpEncodable, Default)]
+    pub struct RangeProof {
+        pub leaf_proofs: Vec<Vec<H256>>,
+    }
+
+    /// Builds the `RangeProof` for the contiguous range
+    /// `[start_index, start_index + range_len)` of the checkpoint's full
+    /// chunk-key leaf list.
+    pub fn range_proof(
+        levels: &[Vec<H256>], start_index: usize, range_len: usize,
+    ) -> RangeProof {
+        RangeProof {
+            leaf_proofs: (start_index..start_index + range_len)
+                .map(|index| leaf_proof(levels, index))
+                .collect(),
+        }
+    }
+
+    /// Verifies that every entry of `leaves` (the chunk-key hashes of a
+    /// ranged response) is really part of the tree committed to by `root`,
+    /// at its claimed position starting from `start_index`. `root` must be
+    /// obtained independently of the peer serving `leaves`/`proof` (e.g.
+    /// from trusted checkpoint metadata) — a root taken from the same
+    /// response it is meant to authenticate gives a malicious peer no
+    /// reason to ever fail this check.
+    pub fn verify_range(
+        leaves: &[H256], start_index: usize, proof: &RangeProof, root: H256,
+    ) -> bool {
+        if leaves.len() != proof.leaf_proofs.len() {
+            return false;
+        }
+        leaves.iter().zip(proof.leaf_proofs.iter()).enumerate().all(
+            |(offset, (leaf, leaf_proof))| {
+                verify_leaf(*leaf, start_index + offset, leaf_proof, root)
+            },
+        )
+    }
+}
+
+/// The reward-epoch count and blame-vector schema a `SnapshotManifestRequest`
+/// expects, one variant per fork that changes them. Following the
+/// superstruct-style "one enum over per-fork variants" convention, decoding
+/// branches on `version()` instead of every node silently assuming a single
+/// global `REWARD_EPOCH_COUNT` — a hard fork that changes the constant
+/// would otherwise make old and new nodes reject each other's manifests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestParams {
+    /// Schema served to peers that haven't advertised
+    /// `DynamicCapability::ManifestVersion` at all, or advertised a version
+    /// we don't recognize. `reward_epoch_count` is pinned to its pre-fork
+    /// value rather than tracking whatever `REWARD_EPOCH_COUNT` happens to
+    /// be today, since such a peer is by definition not expecting the
+    /// post-fork schema.
+    V0 { reward_epoch_count: u64 },
+    V1 { reward_epoch_count: u64 },
+}
+
+impl ManifestParams {
+    /// The version new requests are built with.
+    pub const CURRENT: ManifestParams =
+        ManifestParams::V1 { reward_epoch_count: REWARD_EPOCH_COUNT };
+
+    /// The reward-epoch count `V0` peers were built against, frozen here so
+    /// it stays correct even after `REWARD_EPOCH_COUNT` changes again.
+    const LEGACY_REWARD_EPOCH_COUNT: u64 = 5;
+
+    pub fn version(&self) -> u8 {
+        match self {
+            ManifestParams::V0 { .. } => 0,
+            ManifestParams::V1 { .. } => 1,
+        }
+    }
+
+    pub fn reward_epoch_count(&self) -> u64 {
+        match self {
+            ManifestParams::V0 { reward_epoch_count }
+            | ManifestParams::V1 { reward_epoch_count } => *reward_epoch_count,
+        }
+    }
+}
+
+impl Encodable for ManifestParams {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        match self {
+            ManifestParams::V0 { reward_epoch_count } => {
+                s.begin_list(2).append(&0u8).append(reward_epoch_count);
+            }
+            ManifestParams::V1 { reward_epoch_count } => {
+                s.begin_list(2).append(&1u8).append(reward_epoch_count);
+            }
+        }
+    }
+}
+
+impl Decodable for ManifestParams {
+    fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        match rlp.val_at::<u8>(0)? {
+            0 => Ok(ManifestParams::V0 {
+                reward_epoch_count: rlp.val_at(1)?,
+            }),
+            1 => Ok(ManifestParams::V1 {
+                reward_epoch_count: rlp.val_at(1)?,
+            }),
+            // `version` is attacker-controlled; a `&'static str` avoids
+            // leaking heap memory for every malformed message (`Box::leak`
+            // would let a peer force unbounded allocation growth).
+            _ => Err(DecoderError::Custom(
+                "unsupported ManifestParams version",
+            )),
+        }
+    }
+}
+
+/// The reward-epoch count and blame-vector schema a peer understands is
+/// negotiated out-of-band through `DynamicCapability`
+/// (`DynamicCapability::ManifestVersion`), not carried as a wire field on
+/// `SnapshotManifestRequest`. Appending a field to that RLP-derived struct
+/// would be a breaking wire change: an old, 4-field request fails to decode
+/// on a new node (`RlpIsTooShort`) instead of falling back to
+/// pre-negotiation behavior, which silently reintroduces the very
+/// incompatibility this is meant to fix. Looking the version up from the
+/// peer's advertised capability set keeps the request shape unchanged; the
+/// `SnapshotManifestResponse` does carry the negotiated params back in its
+/// `manifest_params` field.
+fn negotiated_manifest_params(ctx: &Context) -> ManifestParams {
+    match ctx.manager.peer_manifest_version(ctx.peer) {
+        Some(1) => ManifestParams::CURRENT,
+        // Peer hasn't advertised `DynamicCapability::ManifestVersion`, or
+        // advertised one we don't recognize: fall back to the pre-fork
+        // schema so we keep interoperating with it.
+        _ => ManifestParams::V0 {
+            reward_epoch_count: ManifestParams::LEGACY_REWARD_EPOCH_COUNT,
+        },
+    }
+}
 
 #[derive(Debug, Clone, RlpDecodable, RlpEncodable)]
 pub struct SnapshotManifestRequest {
@@ -35,6 +414,16 @@ build_has_request_id_impl! { SnapshotManifestRequest }
 
 impl Handleable for SnapshotManifestRequest {
     fn handle(self, ctx: &Context) -> Result<(), Error> {
+        if !check_manifest_request_rate_limit(ctx) {
+            debug!(
+                "drop SnapshotManifestRequest from peer={}: rate limit exceeded",
+                ctx.peer
+            );
+            return Ok(());
+        }
+
+        let manifest_params = negotiated_manifest_params(ctx);
+
         let manifest = match RangedManifest::load(
             &self.checkpoint,
             self.start_chunk.clone(),
@@ -43,13 +432,26 @@ impl Handleable for SnapshotManifestRequest {
             _ => RangedManifest::default(),
         };
 
-        let (state_blame_vec, receipt_blame_vec, bloom_blame_vec) =
-            self.get_blame_states(ctx).unwrap_or_default();
-        let block_receipts = self.get_block_receipts(ctx).unwrap_or_default();
+        // Anchor this range into the single Merkle root committed for the
+        // checkpoint so the requester can detect a peer that swapped or
+        // corrupted chunk keys before accepting the range.
+        let (manifest_root, range_proof) = manifest
+            .commitment_proof(&self.checkpoint)
+            .unwrap_or_default();
+
+        let (state_blame_vec, receipt_blame_vec, bloom_blame_vec) = self
+            .get_blame_states(ctx, manifest_params)
+            .unwrap_or_default();
+        let block_receipts = self
+            .get_block_receipts(ctx, manifest_params)
+            .unwrap_or_default();
         ctx.send_response(&SnapshotManifestResponse {
             request_id: self.request_id,
             checkpoint: self.checkpoint.clone(),
             manifest,
+            manifest_root,
+            range_proof,
+            manifest_params,
             state_blame_vec,
             receipt_blame_vec,
             bloom_blame_vec,
@@ -79,12 +481,30 @@ impl SnapshotManifestRequest {
         }
     }
 
+    /// Builds the request that should kick off restoration for
+    /// `checkpoint`: resumes from previously persisted restoration
+    /// progress if any exists (e.g. left over from before a restart)
+    /// instead of always re-downloading every range from scratch. This is
+    /// the single entry point the sync-start path (outside this module)
+    /// must call in place of `new` when beginning checkpoint restoration,
+    /// or a restart can never resume in-flight progress.
+    pub fn new_resuming(
+        ctx: &Context, checkpoint: H256, trusted_blame_block: H256,
+    ) -> Self {
+        match manifest_restoration_resume_start_chunk(ctx, &checkpoint) {
+            Some(start_chunk) => {
+                Self::new_with_start_chunk(checkpoint, start_chunk)
+            }
+            None => Self::new(checkpoint, trusted_blame_block),
+        }
+    }
+
     fn get_block_receipts(
-        &self, ctx: &Context,
+        &self, ctx: &Context, manifest_params: ManifestParams,
     ) -> Option<Vec<BlockExecutionResult>> {
         let mut epoch_receipts = Vec::new();
         let mut epoch_hash = self.checkpoint;
-        for _ in 0..REWARD_EPOCH_COUNT {
+        for _ in 0..manifest_params.reward_epoch_count() {
             if let Some(block) =
                 ctx.manager.graph.data_man.block_header_by_hash(&epoch_hash)
             {
@@ -140,7 +560,7 @@ impl SnapshotManifestRequest {
     /// another peer to send the request; otherwise return a state_blame_vec
     /// of the requested block
     fn get_blame_states(
-        &self, ctx: &Context,
+        &self, ctx: &Context, manifest_params: ManifestParams,
     ) -> Option<(Vec<H256>, Vec<H256>, Vec<H256>)> {
         let trusted_block = ctx
             .manager
@@ -159,15 +579,32 @@ impl SnapshotManifestRequest {
             );
             return None;
         }
+        let height_gap = trusted_block.height() - checkpoint_block.height();
+        let max_trusted_blame_block_gap =
+            ctx.manager.protocol_config.max_trusted_blame_block_gap;
+        if height_gap > max_trusted_blame_block_gap {
+            warn!(
+                "reject snapshot manifest request from peer={}: trusted_blame_block height gap {} exceeds cap {}",
+                ctx.peer, height_gap, max_trusted_blame_block_gap
+            );
+            return None;
+        }
         let mut loop_cnt = if checkpoint_block.height() == 0 {
-            trusted_block.height() - checkpoint_block.height() + 1
+            height_gap + 1
         } else {
-            trusted_block.height() - checkpoint_block.height()
-                + REWARD_EPOCH_COUNT
+            height_gap + manifest_params.reward_epoch_count()
         };
         if loop_cnt < trusted_block.blame() as u64 + 1 {
             loop_cnt = trusted_block.blame() as u64 + 1;
         }
+        let max_manifest_loop_cnt = ctx.manager.protocol_config.max_manifest_loop_cnt;
+        if loop_cnt > max_manifest_loop_cnt {
+            warn!(
+                "reject snapshot manifest request from peer={}: loop_cnt {} exceeds cap {}",
+                ctx.peer, loop_cnt, max_manifest_loop_cnt
+            );
+            return None;
+        }
 
         let mut state_blame_vec = Vec::with_capacity(loop_cnt as usize);
         let mut receipt_blame_vec = Vec::with_capacity(loop_cnt as usize);
@@ -234,3 +671,461 @@ impl Request for SnapshotManifestRequest {
         )))
     }
 }
+
+/// A single contiguous, disjoint slice of the checkpoint's chunk-key
+/// ordering, fetched via one ranged `SnapshotManifestRequest`. Both bounds
+/// are concrete keys taken from the full ordering learned by the
+/// page-by-page discovery walk (`on_manifest_discovery_page`), so ranges
+/// handed out by `ManifestRangeDownloadScheduler` never overlap.
+#[derive(Debug, Clone)]
+struct ManifestRange {
+    start_chunk: ChunkKey,
+    /// The last key this range is expected to cover. `SnapshotManifestRequest`
+    /// has no wire field for an end bound, so a serving peer may return
+    /// chunks past it; `on_range_received` trims the response at this key
+    /// instead of trusting the peer to stop here.
+    end_chunk: ChunkKey,
+}
+
+/// Schedules ranged `SnapshotManifestRequest`s across every peer that
+/// advertises `DynamicCapability::ServeCheckpoint` for a checkpoint, instead
+/// of fetching one range at a time from a single peer. Once the discovery
+/// walk establishes the full chunk-key ordering, the remaining key
+/// space is partitioned into disjoint ranges and requested concurrently;
+/// a range whose peer times out or errors is put back at the front of the
+/// queue and reissued to a different peer. With only one capable peer this
+/// degenerates to the original serial behavior.
+pub(crate) struct ManifestRangeDownloadScheduler {
+    checkpoint: H256,
+    pending: VecDeque<ManifestRange>,
+    inflight: HashMap<NodeId, ManifestRange>,
+    max_parallel_ranges: usize,
+    /// Every chunk key accepted so far, across every range and every
+    /// reissue. A reissued range (after a timeout) can race with a late
+    /// response for the range it replaced; this is what actually
+    /// deduplicates the resulting content, rather than the inflight map
+    /// (which only tracks one range per peer, not per key).
+    received: HashSet<ChunkKey>,
+}
+
+impl ManifestRangeDownloadScheduler {
+    /// Partitions `ordered_chunk_keys` (the full key ordering learned from
+    /// the discovery walk) into up to `max_parallel_ranges`
+    /// disjoint ranges.
+    pub fn new(
+        checkpoint: H256, ordered_chunk_keys: &[ChunkKey],
+        max_parallel_ranges: usize,
+    ) -> Self {
+        let max_parallel_ranges = max_parallel_ranges.max(1);
+        let mut pending = VecDeque::new();
+        if !ordered_chunk_keys.is_empty() {
+            let range_len = (ordered_chunk_keys.len()
+                + max_parallel_ranges
+                - 1)
+                / max_parallel_ranges;
+            for chunk in ordered_chunk_keys.chunks(range_len.max(1)) {
+                pending.push_back(ManifestRange {
+                    start_chunk: chunk[0].clone(),
+                    end_chunk: chunk.last().unwrap().clone(),
+                });
+            }
+        }
+        ManifestRangeDownloadScheduler {
+            checkpoint,
+            pending,
+            inflight: HashMap::new(),
+            max_parallel_ranges,
+            received: HashSet::new(),
+        }
+    }
+
+    /// Returns `true` once every range has been received and there is
+    /// nothing left in flight.
+    pub fn is_complete(&self) -> bool {
+        self.pending.is_empty() && self.inflight.is_empty()
+    }
+
+    /// Assigns as many pending ranges as possible to the given idle,
+    /// capable peers (up to `max_parallel_ranges` concurrent ranges
+    /// overall), returning the requests to send. Falls back to handing out
+    /// a single range at a time when only one peer is available.
+    pub fn schedule(
+        &mut self, idle_capable_peers: &[NodeId],
+    ) -> Vec<(NodeId, SnapshotManifestRequest)> {
+        let mut requests = Vec::new();
+        for &peer in idle_capable_peers {
+            if self.inflight.len() >= self.max_parallel_ranges {
+                break;
+            }
+            if self.inflight.contains_key(&peer) {
+                continue;
+            }
+            let range = match self.pending.pop_front() {
+                Some(range) => range,
+                None => break,
+            };
+            let request = SnapshotManifestRequest::new_with_start_chunk(
+                self.checkpoint.clone(),
+                range.start_chunk.clone(),
+            );
+            self.inflight.insert(peer, range);
+            requests.push((peer, request));
+        }
+        requests
+    }
+
+    /// Call when `peer`'s range response arrives, with the chunk keys it
+    /// carried in order. Returns the subset that is both newly-seen (not
+    /// already accepted from an earlier reissue) and within this range's
+    /// bound, which is what the caller should actually apply to restoration
+    /// progress. If the peer stopped before `end_chunk` (a truncated or
+    /// partial response), the unreceived remainder is requeued so it can be
+    /// reissued, ideally to a different peer.
+    pub fn on_range_received(
+        &mut self, peer: &NodeId, received_chunk_keys: &[ChunkKey],
+    ) -> Vec<ChunkKey> {
+        let range = match self.inflight.remove(peer) {
+            Some(range) => range,
+            None => return Vec::new(),
+        };
+        let mut fresh = Vec::new();
+        let mut reached_end = false;
+        for key in received_chunk_keys {
+            if self.received.insert(key.clone()) {
+                fresh.push(key.clone());
+            }
+            if *key == range.end_chunk {
+                reached_end = true;
+                break;
+            }
+        }
+        if !reached_end {
+            match fresh.last().cloned() {
+                Some(resume_from) => self.pending.push_front(ManifestRange {
+                    start_chunk: resume_from,
+                    end_chunk: range.end_chunk,
+                }),
+                None => self.pending.push_front(range),
+            }
+        }
+        fresh
+    }
+
+    /// Call when `peer` times out or errors while serving its assigned
+    /// range. The range is put back at the front of the pending queue so
+    /// it is the next one reissued, ideally to a different peer.
+    pub fn on_range_failed(&mut self, peer: &NodeId) {
+        if let Some(range) = self.inflight.remove(peer) {
+            self.pending.push_front(range);
+        }
+    }
+}
+
+lazy_static! {
+    /// One active `ManifestRangeDownloadScheduler` per checkpoint currently
+    /// being restored. Started from `SnapshotManifestResponse::handle` once
+    /// the discovery walk reaches a page reporting `is_last` and so
+    /// establishes the full chunk-key ordering; entries are removed once
+    /// restoration of that checkpoint
+    /// completes or is aborted.
+    static ref MANIFEST_RANGE_SCHEDULERS: Mutex<HashMap<H256, ManifestRangeDownloadScheduler>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Starts ranged, multi-peer download of `checkpoint`'s manifest once the
+/// full chunk-key ordering is known, replacing any scheduler already
+/// running for it.
+pub(crate) fn start_manifest_range_download(
+    checkpoint: H256, ordered_chunk_keys: &[ChunkKey],
+    max_parallel_ranges: usize,
+) {
+    MANIFEST_RANGE_SCHEDULERS.lock().insert(
+        checkpoint,
+        ManifestRangeDownloadScheduler::new(
+            checkpoint,
+            ordered_chunk_keys,
+            max_parallel_ranges,
+        ),
+    );
+}
+
+/// Feeds a verified range response into `checkpoint`'s scheduler, returning
+/// the newly-seen chunk keys to apply to restoration progress (see
+/// `ManifestRangeDownloadScheduler::on_range_received`), and whether every
+/// range for the checkpoint has now been received.
+pub(crate) fn on_manifest_range_received(
+    checkpoint: &H256, peer: &NodeId, received_chunk_keys: &[ChunkKey],
+) -> (Vec<ChunkKey>, bool) {
+    let mut schedulers = MANIFEST_RANGE_SCHEDULERS.lock();
+    match schedulers.get_mut(checkpoint) {
+        Some(scheduler) => {
+            let fresh =
+                scheduler.on_range_received(peer, received_chunk_keys);
+            (fresh, scheduler.is_complete())
+        }
+        None => (Vec::new(), false),
+    }
+}
+
+/// Removes `checkpoint`'s scheduler once its restoration completes or is
+/// abandoned, so this does not grow without bound over the life of the
+/// process.
+pub(crate) fn forget_manifest_range_scheduler(checkpoint: &H256) {
+    MANIFEST_RANGE_SCHEDULERS.lock().remove(checkpoint);
+}
+
+/// Whether `checkpoint` currently has an active range-download scheduler,
+/// i.e. whether a prior response has already established its full
+/// chunk-key ordering.
+pub(crate) fn manifest_range_scheduler_is_active(checkpoint: &H256) -> bool {
+    MANIFEST_RANGE_SCHEDULERS.lock().contains_key(checkpoint)
+}
+
+/// Assigns `checkpoint`'s next pending ranges to `idle_capable_peers`. The
+/// caller is responsible for actually dispatching the returned requests
+/// through the request manager.
+pub(crate) fn schedule_manifest_ranges(
+    checkpoint: &H256, idle_capable_peers: &[NodeId],
+) -> Vec<(NodeId, SnapshotManifestRequest)> {
+    match MANIFEST_RANGE_SCHEDULERS.lock().get_mut(checkpoint) {
+        Some(scheduler) => scheduler.schedule(idle_capable_peers),
+        None => Vec::new(),
+    }
+}
+
+lazy_static! {
+    /// Chunk keys accumulated so far while walking a checkpoint's manifest
+    /// page by page to discover its full ordering, before that ordering is
+    /// known and `ManifestRangeDownloadScheduler` can partition it into
+    /// disjoint ranges. A single page is only a bounded slice of the
+    /// ordering (see `RangedManifest::load`'s page cap), so the first
+    /// response alone is not enough; this accumulates pages until one
+    /// reports `is_last`.
+    static ref MANIFEST_DISCOVERY: Mutex<HashMap<H256, Vec<ChunkKey>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Feeds one manifest page into `checkpoint`'s discovery walk. Returns
+/// `Ok(full_ordering)` once `is_last` has been reached, ready to hand to
+/// `start_manifest_range_download`; otherwise returns the request that
+/// continues the walk from the last key seen so far.
+pub(crate) fn on_manifest_discovery_page(
+    checkpoint: H256, chunk_keys: &[ChunkKey], is_last: bool,
+) -> Result<Vec<ChunkKey>, SnapshotManifestRequest> {
+    let mut discovery = MANIFEST_DISCOVERY.lock();
+    let accumulated =
+        discovery.entry(checkpoint.clone()).or_insert_with(Vec::new);
+    // A continuation page starts at (and so repeats) the last key of the
+    // previous page; skip it so the ordering holds each key once.
+    let skip = match (accumulated.last(), chunk_keys.first()) {
+        (Some(last), Some(first)) if last == first => 1,
+        _ => 0,
+    };
+    accumulated.extend_from_slice(&chunk_keys[skip..]);
+    if is_last {
+        let full = discovery.remove(&checkpoint).unwrap_or_default();
+        Ok(full)
+    } else {
+        let resume_from = accumulated.last().cloned();
+        drop(discovery);
+        Err(match resume_from {
+            Some(start_chunk) => SnapshotManifestRequest::new_with_start_chunk(
+                checkpoint,
+                start_chunk,
+            ),
+            // An empty, non-last page: nothing learned yet, so restart the
+            // walk from the beginning.
+            None => SnapshotManifestRequest {
+                request_id: 0,
+                checkpoint,
+                start_chunk: None,
+                trusted_blame_block: None,
+            },
+        })
+    }
+}
+
+/// Whether `checkpoint` has a discovery walk in progress (i.e. at least
+/// one manifest page has been accumulated but the full ordering isn't
+/// known yet).
+pub(crate) fn manifest_discovery_in_progress(checkpoint: &H256) -> bool {
+    MANIFEST_DISCOVERY.lock().contains_key(checkpoint)
+}
+
+/// Lifecycle of a checkpoint's manifest restoration, mirroring how
+/// restoration progress is reported so operators can observe it (e.g. over
+/// RPC) the way OpenEthereum reports snapshot restoration over IPC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestorationStatus {
+    /// Ranges are still being downloaded and verified.
+    Ongoing,
+    /// Every range has been received and verified.
+    Completed,
+    /// Restoration was abandoned, e.g. because the checkpoint went stale.
+    Aborted,
+}
+
+impl Default for RestorationStatus {
+    fn default() -> Self { RestorationStatus::Ongoing }
+}
+
+impl Encodable for RestorationStatus {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        let tag: u8 = match self {
+            RestorationStatus::Ongoing => 0,
+            RestorationStatus::Completed => 1,
+            RestorationStatus::Aborted => 2,
+        };
+        s.append(&tag);
+    }
+}
+
+impl Decodable for RestorationStatus {
+    fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        match rlp.as_val::<u8>()? {
+            0 => Ok(RestorationStatus::Ongoing),
+            1 => Ok(RestorationStatus::Completed),
+            2 => Ok(RestorationStatus::Aborted),
+            _ => Err(DecoderError::Custom("invalid RestorationStatus tag")),
+        }
+    }
+}
+
+/// Persisted, resumable progress for a single checkpoint's manifest
+/// restoration: which `ChunkKey` ranges have already been received and
+/// verified, and the blame vectors/receipts accumulated so far. Keyed by
+/// checkpoint hash in the node's database so a restart can resume from the
+/// last boundary (via `SnapshotManifestRequest::new_with_start_chunk`)
+/// instead of re-requesting everything from scratch.
+#[derive(Debug, Clone, Default, RlpDecodable, RlpEncodable)]
+pub struct ManifestRestorationProgress {
+    pub status: RestorationStatus,
+    /// The last chunk key of each range received so far, in order; the
+    /// final entry is the resume point for the next ranged request.
+    pub received_range_ends: Vec<ChunkKey>,
+    pub state_blame_vec: Vec<H256>,
+    pub receipt_blame_vec: Vec<H256>,
+    pub bloom_blame_vec: Vec<H256>,
+    pub block_receipts: Vec<BlockExecutionResult>,
+}
+
+impl ManifestRestorationProgress {
+    /// The `start_chunk` to resume from, or `None` if nothing has been
+    /// received yet and restoration should start from the beginning.
+    pub fn resume_start_chunk(&self) -> Option<ChunkKey> {
+        self.received_range_ends.last().cloned()
+    }
+
+    /// Records a newly received and verified range, appending its
+    /// accumulated blame-vector and receipt data.
+    pub fn record_range(
+        &mut self, range_end: ChunkKey, state_blame_vec: &[H256],
+        receipt_blame_vec: &[H256], bloom_blame_vec: &[H256],
+        block_receipts: &[BlockExecutionResult],
+    ) {
+        self.received_range_ends.push(range_end);
+        self.state_blame_vec.extend_from_slice(state_blame_vec);
+        self.receipt_blame_vec.extend_from_slice(receipt_blame_vec);
+        self.bloom_blame_vec.extend_from_slice(bloom_blame_vec);
+        self.block_receipts.extend_from_slice(block_receipts);
+    }
+
+    pub fn mark_completed(&mut self) {
+        self.status = RestorationStatus::Completed;
+    }
+
+    pub fn mark_aborted(&mut self) { self.status = RestorationStatus::Aborted; }
+}
+
+/// Persists and loads `ManifestRestorationProgress` keyed by checkpoint
+/// hash, so a restart can resume restoration instead of starting over.
+pub(crate) trait ManifestRestorationStore {
+    fn get_manifest_restoration_progress(
+        &self, checkpoint: &H256,
+    ) -> Option<ManifestRestorationProgress>;
+
+    fn set_manifest_restoration_progress(
+        &self, checkpoint: &H256, progress: &ManifestRestorationProgress,
+    );
+
+    fn delete_manifest_restoration_progress(&self, checkpoint: &H256);
+}
+
+/// The node-database-backed `ManifestRestorationStore`, following the
+/// `_from_db`/`_to_db` naming convention `BlockDataManager` already uses
+/// elsewhere in this module (e.g. `consensus_graph_execution_info_from_db`),
+/// so restoration progress genuinely survives a process restart instead of
+/// only living for as long as the process does.
+pub(crate) struct DbManifestRestorationStore<'a> {
+    data_man: &'a BlockDataManager,
+}
+
+impl<'a> DbManifestRestorationStore<'a> {
+    pub fn new(data_man: &'a BlockDataManager) -> Self {
+        DbManifestRestorationStore { data_man }
+    }
+}
+
+impl<'a> ManifestRestorationStore for DbManifestRestorationStore<'a> {
+    fn get_manifest_restoration_progress(
+        &self, checkpoint: &H256,
+    ) -> Option<ManifestRestorationProgress> {
+        self.data_man.manifest_restoration_progress_from_db(checkpoint)
+    }
+
+    fn set_manifest_restoration_progress(
+        &self, checkpoint: &H256, progress: &ManifestRestorationProgress,
+    ) {
+        self.data_man.insert_manifest_restoration_progress_to_db(
+            checkpoint, progress,
+        );
+    }
+
+    fn delete_manifest_restoration_progress(&self, checkpoint: &H256) {
+        self.data_man
+            .delete_manifest_restoration_progress_from_db(checkpoint);
+    }
+}
+
+/// The `start_chunk` a fresh `SnapshotManifestRequest` for `checkpoint`
+/// should resume from, based on whatever progress was already persisted
+/// (e.g. from before a restart).
+pub(crate) fn manifest_restoration_resume_start_chunk(
+    ctx: &Context, checkpoint: &H256,
+) -> Option<ChunkKey> {
+    DbManifestRestorationStore::new(&ctx.manager.graph.data_man)
+        .get_manifest_restoration_progress(checkpoint)
+        .and_then(|progress| progress.resume_start_chunk())
+}
+
+/// Records a newly received and verified range against `checkpoint`'s
+/// persisted restoration progress, creating it if this is the first range
+/// received for it.
+pub(crate) fn record_manifest_restoration_range(
+    ctx: &Context, checkpoint: &H256, range_end: ChunkKey,
+    state_blame_vec: &[H256], receipt_blame_vec: &[H256],
+    bloom_blame_vec: &[H256], block_receipts: &[BlockExecutionResult],
+) {
+    let store = DbManifestRestorationStore::new(&ctx.manager.graph.data_man);
+    let mut progress = store
+        .get_manifest_restoration_progress(checkpoint)
+        .unwrap_or_default();
+    progress.record_range(
+        range_end,
+        state_blame_vec,
+        receipt_blame_vec,
+        bloom_blame_vec,
+        block_receipts,
+    );
+    store.set_manifest_restoration_progress(checkpoint, &progress);
+}
+
+/// Called once `checkpoint`'s restoration completes: deletes its persisted
+/// progress record. No record with `RestorationStatus::Completed` is kept
+/// for it.
+pub(crate) fn mark_manifest_restoration_completed(
+    ctx: &Context, checkpoint: &H256,
+) {
+    DbManifestRestorationStore::new(&ctx.manager.graph.data_man)
+        .delete_manifest_restoration_progress(checkpoint);
+}
@@ -0,0 +1,205 @@
+// Copyright 2019 Conflux Foundation. All rights reserved.
+// Conflux is free software and distributed under GNU General Public License.
+// See http://www.gnu.org/licenses/
+
+use crate::{
+    block_data_manager::BlockExecutionResult,
+    message::{HasRequestId, Message, MsgId, RequestId},
+    sync::{
+        message::{msgid, Context, Handleable},
+        state::{
+            delta::{ChunkKey, RangedManifest},
+            snapshot_manifest_request::{
+                self, manifest_commitment, ManifestParams,
+            },
+        },
+        Error,
+    },
+};
+use cfx_types::H256;
+use keccak_hash::keccak;
+use rlp::Encodable;
+use rlp_derive::{RlpDecodable, RlpEncodable};
+
+/// How many disjoint chunk-key ranges a checkpoint's manifest is
+/// partitioned into, and the most of them that may be in flight at once.
+/// `handle` assigns ranges only to the peer whose response it is
+/// processing, and the scheduler keeps at most one range in flight per
+/// peer, so on this path the ranges are fetched one at a time from that
+/// peer.
+const MAX_PARALLEL_MANIFEST_RANGES: usize = 4;
+
+#[derive(Debug, Clone, RlpDecodable, RlpEncodable)]
+pub struct SnapshotManifestResponse {
+    pub request_id: u64,
+    pub checkpoint: H256,
+    pub manifest: RangedManifest,
+    /// The Merkle root the serving peer claims for `manifest`'s chunk-key
+    /// ordering. This is *not* trusted on its own — see `handle` below —
+    /// it only counts as this peer's vote toward the trusted-root quorum
+    /// (`witness_manifest_root_claim`), and a mismatch against the trusted
+    /// root is logged.
+    pub manifest_root: H256,
+    pub range_proof: manifest_commitment::RangeProof,
+    pub manifest_params: ManifestParams,
+    pub state_blame_vec: Vec<H256>,
+    pub receipt_blame_vec: Vec<H256>,
+    pub bloom_blame_vec: Vec<H256>,
+    pub block_receipts: Vec<BlockExecutionResult>,
+}
+
+build_msg_impl! { SnapshotManifestResponse, msgid::SNAPSHOT_MANIFEST_RESPONSE, "SnapshotManifestResponse" }
+build_has_request_id_impl! { SnapshotManifestResponse }
+
+/// The leaf a chunk key hashes to in the checkpoint's commitment tree.
+/// Must match whatever `RangedManifest::commitment_proof` uses to build the
+/// tree it proves against on the serving side.
+fn chunk_key_leaf(key: &ChunkKey) -> H256 { keccak(key.rlp_bytes()) }
+
+impl Handleable for SnapshotManifestResponse {
+    fn handle(self, ctx: &Context) -> Result<(), Error> {
+        let Some(start_index) =
+            self.manifest.start_chunk_index(&self.checkpoint)
+        else {
+            warn!(
+                "drop SnapshotManifestResponse from peer={}: unknown range start for checkpoint={}",
+                ctx.peer, self.checkpoint
+            );
+            return Ok(());
+        };
+
+        // Register this peer's claimed root towards quorum before
+        // consulting the trusted store — this is the checkpoint/snapshot
+        // metadata path that actually populates it. A single peer's claim
+        // is never enough on its own (see `witness_manifest_root_claim`),
+        // so a malicious peer gains nothing by forging `manifest_root`.
+        snapshot_manifest_request::witness_manifest_root_claim(
+            self.checkpoint,
+            ctx.peer,
+            self.manifest_root,
+        );
+        let Some(trusted_root) =
+            snapshot_manifest_request::trusted_manifest_root(
+                &self.checkpoint,
+            )
+        else {
+            warn!(
+                "drop SnapshotManifestResponse from peer={}: no trusted manifest root for checkpoint={} yet",
+                ctx.peer, self.checkpoint
+            );
+            return Ok(());
+        };
+        if trusted_root != self.manifest_root {
+            debug!(
+                "peer={} advertised manifest_root={:?} that disagrees with trusted root={:?} for checkpoint={}",
+                ctx.peer, self.manifest_root, trusted_root, self.checkpoint
+            );
+        }
+
+        let leaves: Vec<H256> = self
+            .manifest
+            .chunk_keys()
+            .iter()
+            .map(chunk_key_leaf)
+            .collect();
+        if !manifest_commitment::verify_range(
+            &leaves,
+            start_index,
+            &self.range_proof,
+            trusted_root,
+        ) {
+            warn!(
+                "drop SnapshotManifestResponse from peer={}: range proof failed against trusted root for checkpoint={}",
+                ctx.peer, self.checkpoint
+            );
+            return Ok(());
+        }
+
+        let chunk_keys = self.manifest.chunk_keys().to_vec();
+        if !snapshot_manifest_request::manifest_range_scheduler_is_active(
+            &self.checkpoint,
+        ) {
+            // A `RangedManifest` response is only a bounded page (see
+            // `RangedManifest::load`'s page cap), not the checkpoint's full
+            // chunk-key ordering, so the walk must be carried across pages
+            // until one reports `is_last` before the range scheduler can
+            // partition the full key space.
+            match snapshot_manifest_request::on_manifest_discovery_page(
+                self.checkpoint,
+                &chunk_keys,
+                self.manifest.is_last(),
+            ) {
+                Ok(full_ordering) => {
+                    snapshot_manifest_request::start_manifest_range_download(
+                        self.checkpoint,
+                        &full_ordering,
+                        MAX_PARALLEL_MANIFEST_RANGES,
+                    );
+                }
+                Err(continuation) => {
+                    ctx.send_request(ctx.peer, Box::new(continuation));
+                }
+            }
+        } else {
+            let (fresh_keys, complete) =
+                snapshot_manifest_request::on_manifest_range_received(
+                    &self.checkpoint,
+                    &ctx.peer,
+                    &chunk_keys,
+                );
+            debug!(
+                "checkpoint={} accepted {} fresh chunk key(s) from peer={}, restoration complete={}",
+                self.checkpoint, fresh_keys.len(), ctx.peer, complete
+            );
+            // Persist progress so a restart can resume from the last
+            // received range boundary (via
+            // `SnapshotManifestRequest::new_with_start_chunk`) instead of
+            // re-downloading every range from scratch.
+            if let Some(range_end) = fresh_keys.last().cloned() {
+                snapshot_manifest_request::record_manifest_restoration_range(
+                    ctx,
+                    &self.checkpoint,
+                    range_end,
+                    &self.state_blame_vec,
+                    &self.receipt_blame_vec,
+                    &self.bloom_blame_vec,
+                    &self.block_receipts,
+                );
+            }
+            if complete {
+                snapshot_manifest_request::mark_manifest_restoration_completed(
+                    ctx,
+                    &self.checkpoint,
+                );
+                snapshot_manifest_request::forget_manifest_range_scheduler(
+                    &self.checkpoint,
+                );
+                snapshot_manifest_request::forget_trusted_manifest_root(
+                    &self.checkpoint,
+                );
+            }
+        }
+
+        // Hand out any newly-unblocked ranges to this peer and actually
+        // dispatch them through the request manager, rather than only
+        // computing what could be sent next.
+        let next_ranges = snapshot_manifest_request::schedule_manifest_ranges(
+            &self.checkpoint,
+            &[ctx.peer],
+        );
+        debug!(
+            "checkpoint={} dispatching {} range(s)",
+            self.checkpoint,
+            next_ranges.len()
+        );
+        for (peer, request) in next_ranges {
+            ctx.send_request(peer, Box::new(request));
+        }
+
+        debug!(
+            "accepted verified SnapshotManifestResponse from peer={} for checkpoint={}",
+            ctx.peer, self.checkpoint
+        );
+        Ok(())
+    }
+}